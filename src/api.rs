@@ -1,11 +1,12 @@
 use crate::model::{Span, Trace};
+use crate::sampling::Sampler;
 use std::{
     collections::HashMap,
     time::{Duration, UNIX_EPOCH}
 };
 use serde::Serialize;
 
-fn fill_meta(span: &Span, env: Option<String>) -> HashMap<String, String> {
+fn fill_meta(span: &Span, env: Option<String>, obfuscate_sql: bool) -> HashMap<String, String> {
     let mut meta = HashMap::new();
     if let Some(env) = env {
         meta.insert("env".to_string(), env);
@@ -22,7 +23,12 @@ fn fill_meta(span: &Span, env: Option<String>) -> HashMap<String, String> {
         meta.insert("error.stack".to_string(), error.stack.clone());
     }
     if let Some(sql) = &span.sql {
-        meta.insert("sql.query".to_string(), sql.query.clone());
+        let query = if obfuscate_sql {
+            crate::sql::obfuscate_query(&sql.query)
+        } else {
+            sql.query.clone()
+        };
+        meta.insert("sql.query".to_string(), query);
         meta.insert("sql.rows".to_string(), sql.rows.clone());
         meta.insert("sql.db".to_string(), sql.db.clone());
     }
@@ -32,13 +38,26 @@ fn fill_meta(span: &Span, env: Option<String>) -> HashMap<String, String> {
     meta
 }
 
-fn fill_metrics(priority: u32) -> HashMap<String, f64> {
+fn fill_metrics(
+    priority: u32,
+    is_top_level: bool,
+    applied_rate: f64,
+    rule_based: bool,
+) -> HashMap<String, f64> {
     let mut metrics = HashMap::new();
     metrics.insert("_sampling_priority_v1".to_string(), f64::from(priority));
+    if is_top_level {
+        metrics.insert("_top_level".to_string(), 1.0);
+    }
+    if rule_based {
+        metrics.insert("_dd.rule_psr".to_string(), applied_rate);
+    } else {
+        metrics.insert("_dd.agent_psr".to_string(), applied_rate);
+    }
     metrics
 }
 
-fn duration_to_nanos(duration: Duration) -> u64 {
+pub(crate) fn duration_to_nanos(duration: Duration) -> u64 {
     duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64
 }
 
@@ -46,13 +65,60 @@ fn duration_to_nanos(duration: Duration) -> u64 {
 pub struct RawTrace(Vec<RawSpan>);
 
 impl RawTrace {
-    pub fn from_trace(trace: &Trace, service: &String, env: &Option<String>) -> RawTrace {
+    pub fn from_trace(
+        trace: &Trace,
+        service: &str,
+        env: &Option<String>,
+        obfuscate_sql: bool,
+        sampler: &Sampler,
+    ) -> RawTrace {
+        let spans_by_id: HashMap<u64, &Span> =
+            trace.spans.iter().map(|span| (span.id, span)).collect();
+        let resource = trace
+            .spans
+            .iter()
+            .find(|span| span.parent_id.is_none())
+            .or_else(|| trace.spans.first())
+            .map(|span| span.resource.as_str())
+            .unwrap_or_default();
+        let (priority, applied_rate, rule_based) = sampler.sample(trace, service, resource);
         RawTrace(
-            trace.spans.iter().map(|span| RawSpan::from_span(span, trace, service, env)).collect()
+            trace
+                .spans
+                .iter()
+                .map(|span| {
+                    RawSpan::from_span(
+                        span,
+                        &spans_by_id,
+                        trace,
+                        service,
+                        env,
+                        obfuscate_sql,
+                        priority,
+                        applied_rate,
+                        rule_based,
+                    )
+                })
+                .collect(),
         )
     }
 }
 
+pub(crate) fn resolve_service(span: &Span, default_service: &str) -> String {
+    span.service.clone().unwrap_or_else(|| default_service.to_string())
+}
+
+pub(crate) fn is_top_level(
+    span: &Span,
+    spans_by_id: &HashMap<u64, &Span>,
+    default_service: &str,
+) -> bool {
+    match span.parent_id.and_then(|parent_id| spans_by_id.get(&parent_id)) {
+        None => true,
+        Some(parent) => resolve_service(parent, default_service) != resolve_service(span, default_service),
+    }
+}
+
 #[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct RawSpan {
     service: String,
@@ -70,9 +136,20 @@ pub struct RawSpan {
 }
 
 impl RawSpan {
-    pub fn from_span(span: &Span, trace: &Trace, service: &String, env: &Option<String>) -> RawSpan {
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_span(
+        span: &Span,
+        spans_by_id: &HashMap<u64, &Span>,
+        trace: &Trace,
+        service: &str,
+        env: &Option<String>,
+        obfuscate_sql: bool,
+        priority: u32,
+        applied_rate: f64,
+        rule_based: bool,
+    ) -> RawSpan {
         RawSpan {
-            service: service.clone(),
+            service: resolve_service(span, service),
             trace_id: trace.id,
             span_id: span.id,
             name: span.name.clone(),
@@ -82,8 +159,13 @@ impl RawSpan {
             duration: duration_to_nanos(span.duration),
             error: if span.error.is_some() { 1 } else { 0 },
             r#type: span.r#type.clone(),
-            meta: fill_meta(&span, env.clone()),
-            metrics: fill_metrics(trace.priority),
+            meta: fill_meta(span, env.clone(), obfuscate_sql),
+            metrics: fill_metrics(
+                priority,
+                is_top_level(span, spans_by_id, service),
+                applied_rate,
+                rule_based,
+            ),
         }
     }
 }
@@ -117,6 +199,7 @@ mod tests {
                 start: SystemTime::now(),
                 duration: Duration::from_secs(2),
                 parent_id: None,
+                service: None,
                 http: Some(HttpInfo {
                     url: String::from("/home/v3/2?trace=true"),
                     method: String::from("GET"),
@@ -143,6 +226,10 @@ mod tests {
                 "_sampling_priority_v1".to_string(),
                 f64::from(trace.priority),
             );
+            if span.parent_id.is_none() {
+                metrics.insert("_top_level".to_string(), 1.0);
+            }
+            metrics.insert("_dd.agent_psr".to_string(), 1.0);
 
             expected.push(RawSpan {
                 trace_id: trace.id,
@@ -155,12 +242,204 @@ mod tests {
                 start: duration_to_nanos(span.start.duration_since(UNIX_EPOCH).unwrap()),
                 duration: duration_to_nanos(span.duration),
                 error: 0,
-                meta: meta,
-                metrics: metrics,
+                meta,
+                metrics,
             });
         }
-        let raw_spans = RawTrace::from_trace(&trace, &config.service, &config.env);
+        let sampler = Sampler::new(1.0);
+        let raw_spans = RawTrace::from_trace(
+            &trace,
+            &config.service,
+            &config.env,
+            config.obfuscate_sql,
+            &sampler,
+        );
 
         assert_eq!(raw_spans.0, expected);
     }
+
+    #[test]
+    fn test_obfuscates_sql_query_by_default() {
+        let config = Config {
+            service: String::from("service_name"),
+            ..Default::default()
+        };
+        let trace = Trace {
+            id: rand::thread_rng().gen::<u64>(),
+            priority: 1,
+            spans: vec![Span {
+                id: rand::thread_rng().gen::<u64>(),
+                name: String::from("query"),
+                resource: String::from("SELECT * FROM users"),
+                r#type: String::from("sql"),
+                start: SystemTime::now(),
+                duration: Duration::from_millis(5),
+                parent_id: None,
+                service: None,
+                http: None,
+                error: None,
+                sql: Some(crate::model::SqlInfo {
+                    query: String::from("SELECT * FROM users WHERE email = 'a@b.com'"),
+                    rows: String::from("1"),
+                    db: String::from("postgres"),
+                }),
+                tags: HashMap::new(),
+            }],
+        };
+
+        let sampler = Sampler::new(1.0);
+        let raw_spans = RawTrace::from_trace(
+            &trace,
+            &config.service,
+            &config.env,
+            config.obfuscate_sql,
+            &sampler,
+        );
+
+        assert_eq!(
+            raw_spans.0[0].meta.get("sql.query").unwrap(),
+            "SELECT * FROM users WHERE email = ?"
+        );
+    }
+
+    #[test]
+    fn test_sampling_priority_and_applied_rate_are_emitted() {
+        let config = Config {
+            service: String::from("service_name"),
+            sample_rate: 0.0,
+            ..Default::default()
+        };
+        let trace = Trace {
+            id: rand::thread_rng().gen::<u64>(),
+            priority: 0,
+            spans: vec![Span {
+                id: rand::thread_rng().gen::<u64>(),
+                name: String::from("request"),
+                resource: String::from("/home/v3"),
+                r#type: String::from("web"),
+                start: SystemTime::now(),
+                duration: Duration::from_secs(1),
+                parent_id: None,
+                service: None,
+                http: None,
+                error: None,
+                sql: None,
+                tags: HashMap::new(),
+            }],
+        };
+
+        let sampler = Sampler::new(config.sample_rate);
+        let raw_spans = RawTrace::from_trace(
+            &trace,
+            &config.service,
+            &config.env,
+            config.obfuscate_sql,
+            &sampler,
+        );
+
+        let metrics = &raw_spans.0[0].metrics;
+        assert_eq!(metrics.get("_sampling_priority_v1"), Some(&0.0));
+        assert_eq!(metrics.get("_dd.agent_psr"), Some(&0.0));
+    }
+
+    fn child_span(parent_id: u64, service: Option<String>) -> Span {
+        Span {
+            id: rand::thread_rng().gen::<u64>(),
+            name: String::from("child"),
+            resource: String::from("/child"),
+            r#type: String::from("web"),
+            start: SystemTime::now(),
+            duration: Duration::from_millis(1),
+            parent_id: Some(parent_id),
+            service,
+            http: None,
+            error: None,
+            sql: None,
+            tags: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_child_span_is_not_top_level_when_service_matches_parent() {
+        let config = Config {
+            service: String::from("service_name"),
+            ..Default::default()
+        };
+        let parent_id = rand::thread_rng().gen::<u64>();
+        let trace = Trace {
+            id: rand::thread_rng().gen::<u64>(),
+            priority: 1,
+            spans: vec![
+                Span {
+                    id: parent_id,
+                    name: String::from("parent"),
+                    resource: String::from("/parent"),
+                    r#type: String::from("web"),
+                    start: SystemTime::now(),
+                    duration: Duration::from_millis(2),
+                    parent_id: None,
+                    service: None,
+                    http: None,
+                    error: None,
+                    sql: None,
+                    tags: HashMap::new(),
+                },
+                child_span(parent_id, None),
+            ],
+        };
+
+        let sampler = Sampler::new(1.0);
+        let raw_spans = RawTrace::from_trace(
+            &trace,
+            &config.service,
+            &config.env,
+            config.obfuscate_sql,
+            &sampler,
+        );
+
+        let child = raw_spans.0.iter().find(|span| span.parent_id.is_some()).unwrap();
+        assert_eq!(child.metrics.get("_top_level"), None);
+    }
+
+    #[test]
+    fn test_child_span_is_top_level_when_service_differs_from_parent() {
+        let config = Config {
+            service: String::from("service_name"),
+            ..Default::default()
+        };
+        let parent_id = rand::thread_rng().gen::<u64>();
+        let trace = Trace {
+            id: rand::thread_rng().gen::<u64>(),
+            priority: 1,
+            spans: vec![
+                Span {
+                    id: parent_id,
+                    name: String::from("parent"),
+                    resource: String::from("/parent"),
+                    r#type: String::from("web"),
+                    start: SystemTime::now(),
+                    duration: Duration::from_millis(2),
+                    parent_id: None,
+                    service: None,
+                    http: None,
+                    error: None,
+                    sql: None,
+                    tags: HashMap::new(),
+                },
+                child_span(parent_id, Some(String::from("downstream_service"))),
+            ],
+        };
+
+        let sampler = Sampler::new(1.0);
+        let raw_spans = RawTrace::from_trace(
+            &trace,
+            &config.service,
+            &config.env,
+            config.obfuscate_sql,
+            &sampler,
+        );
+
+        let child = raw_spans.0.iter().find(|span| span.parent_id.is_some()).unwrap();
+        assert_eq!(child.metrics.get("_top_level"), Some(&1.0));
+    }
 }
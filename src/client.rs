@@ -0,0 +1,30 @@
+use crate::sampling::SamplingRule;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub service: String,
+    pub env: Option<String>,
+    pub host: String,
+    pub port: u16,
+    /// Whether `sql.query` meta is obfuscated before spans leave the process.
+    pub obfuscate_sql: bool,
+    /// Default fraction of traces to keep, in `[0.0, 1.0]`, when no `sampling_rules`
+    /// entry matches a trace's service/resource.
+    pub sample_rate: f64,
+    /// Per-service/resource overrides of `sample_rate`, checked in order.
+    pub sampling_rules: Vec<SamplingRule>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            service: String::new(),
+            env: None,
+            host: String::from("localhost"),
+            port: 8126,
+            obfuscate_sql: true,
+            sample_rate: 1.0,
+            sampling_rules: Vec::new(),
+        }
+    }
+}
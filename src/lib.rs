@@ -0,0 +1,6 @@
+pub mod api;
+pub mod client;
+pub mod model;
+pub mod sampling;
+pub mod sql;
+pub mod stats;
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    pub id: u64,
+    pub priority: u32,
+    pub spans: Vec<Span>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub id: u64,
+    pub parent_id: Option<u64>,
+    pub service: Option<String>,
+    pub name: String,
+    pub resource: String,
+    pub r#type: String,
+    pub start: SystemTime,
+    pub duration: Duration,
+    pub http: Option<HttpInfo>,
+    pub error: Option<ErrorInfo>,
+    pub sql: Option<SqlInfo>,
+    pub tags: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpInfo {
+    pub url: String,
+    pub method: String,
+    pub status_code: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorInfo {
+    pub r#type: String,
+    pub msg: String,
+    pub stack: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlInfo {
+    pub query: String,
+    pub rows: String,
+    pub db: String,
+}
@@ -0,0 +1,157 @@
+use crate::model::Trace;
+
+const SAMPLER_HASH_MULTIPLIER: u64 = 1_111_111_111_111_111_111;
+
+pub const PRIORITY_AUTO_DROP: u32 = 0;
+pub const PRIORITY_AUTO_KEEP: u32 = 1;
+pub const PRIORITY_USER_KEEP: u32 = 2;
+
+/// A sample rate override for traces matching a given service and/or resource; either
+/// field left `None` matches anything.
+#[derive(Debug, Clone)]
+pub struct SamplingRule {
+    pub service: Option<String>,
+    pub resource: Option<String>,
+    pub sample_rate: f64,
+}
+
+/// Derives a deterministic keep/drop decision from a trace id, so the same trace is
+/// sampled identically across every service that sees it, instead of each service
+/// deciding for itself.
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    default_rate: f64,
+    rules: Vec<SamplingRule>,
+}
+
+impl Sampler {
+    pub fn new(default_rate: f64) -> Sampler {
+        Sampler {
+            default_rate: default_rate.clamp(0.0, 1.0),
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn with_rules(default_rate: f64, rules: Vec<SamplingRule>) -> Sampler {
+        Sampler {
+            default_rate: default_rate.clamp(0.0, 1.0),
+            rules,
+        }
+    }
+
+    fn rate_for(&self, service: &str, resource: &str) -> (f64, bool) {
+        match self.rules.iter().find(|rule| {
+            rule.service.as_deref().is_none_or(|s| s == service)
+                && rule.resource.as_deref().is_none_or(|r| r == resource)
+        }) {
+            Some(rule) => (rule.sample_rate.clamp(0.0, 1.0), true),
+            None => (self.default_rate, false),
+        }
+    }
+
+    /// Returns `(priority, applied_rate, rule_based)` for `trace`. A trace already
+    /// carrying a user-assigned priority (`PRIORITY_USER_KEEP` or above) is always
+    /// honored as-is; otherwise the keep/drop decision is derived from `trace.id` and
+    /// the matching rate, so re-sampling the same trace elsewhere yields the same result.
+    pub fn sample(&self, trace: &Trace, service: &str, resource: &str) -> (u32, f64, bool) {
+        if trace.priority >= PRIORITY_USER_KEEP {
+            return (trace.priority, 1.0, false);
+        }
+
+        let (rate, rule_based) = self.rate_for(service, resource);
+        let hashed = trace.id.wrapping_mul(SAMPLER_HASH_MULTIPLIER);
+        let threshold = (rate * 2f64.powi(64)) as u128;
+        let priority = if (hashed as u128) < threshold {
+            PRIORITY_AUTO_KEEP
+        } else {
+            PRIORITY_AUTO_DROP
+        };
+        (priority, rate, rule_based)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_is_deterministic_for_a_given_trace_id() {
+        let sampler = Sampler::new(0.5);
+        let trace = Trace {
+            id: 123456789,
+            priority: 0,
+            spans: Vec::new(),
+        };
+
+        let first = sampler.sample(&trace, "service", "resource");
+        let second = sampler.sample(&trace, "service", "resource");
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_full_rate_always_keeps() {
+        let sampler = Sampler::new(1.0);
+        let trace = Trace {
+            id: 42,
+            priority: 0,
+            spans: Vec::new(),
+        };
+
+        assert_eq!(
+            sampler.sample(&trace, "service", "resource"),
+            (PRIORITY_AUTO_KEEP, 1.0, false)
+        );
+    }
+
+    #[test]
+    fn test_zero_rate_always_drops() {
+        let sampler = Sampler::new(0.0);
+        let trace = Trace {
+            id: 42,
+            priority: 0,
+            spans: Vec::new(),
+        };
+
+        assert_eq!(
+            sampler.sample(&trace, "service", "resource"),
+            (PRIORITY_AUTO_DROP, 0.0, false)
+        );
+    }
+
+    #[test]
+    fn test_user_keep_priority_is_preserved() {
+        let sampler = Sampler::new(0.0);
+        let trace = Trace {
+            id: 42,
+            priority: PRIORITY_USER_KEEP,
+            spans: Vec::new(),
+        };
+
+        assert_eq!(
+            sampler.sample(&trace, "service", "resource"),
+            (PRIORITY_USER_KEEP, 1.0, false)
+        );
+    }
+
+    #[test]
+    fn test_rule_rate_overrides_default_rate() {
+        let sampler = Sampler::with_rules(
+            0.0,
+            vec![SamplingRule {
+                service: Some(String::from("service")),
+                resource: None,
+                sample_rate: 1.0,
+            }],
+        );
+        let trace = Trace {
+            id: 42,
+            priority: 0,
+            spans: Vec::new(),
+        };
+
+        assert_eq!(
+            sampler.sample(&trace, "service", "resource"),
+            (PRIORITY_AUTO_KEEP, 1.0, true)
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// Obfuscates a SQL query the same way the agent's quantizer does, so that literal
+/// values (emails, tokens, ids) never leave the process in `sql.query` meta.
+pub fn obfuscate_query(query: &str) -> String {
+    let without_comments = strip_comments(query);
+    let without_binds = replace_bind_variables(&without_comments);
+    let without_literals = replace_literals(&without_binds);
+    collapse_in_lists(&without_literals)
+}
+
+fn strip_comments(query: &str) -> String {
+    static BLOCK_COMMENT: OnceLock<Regex> = OnceLock::new();
+    static LINE_COMMENT: OnceLock<Regex> = OnceLock::new();
+
+    let block_comment = BLOCK_COMMENT.get_or_init(|| Regex::new(r"(?s)/\*.*?\*/").unwrap());
+    let line_comment = LINE_COMMENT.get_or_init(|| Regex::new(r"--[^\n]*").unwrap());
+
+    let without_block = block_comment.replace_all(query, "");
+    line_comment.replace_all(&without_block, "").into_owned()
+}
+
+fn replace_bind_variables(query: &str) -> String {
+    static BIND_VARIABLE: OnceLock<Regex> = OnceLock::new();
+    // The `cast` alternative is tried first so a `::type` cast is matched (and left
+    // untouched) before the bare `:name` alternative can mistake its second colon for
+    // the start of a bind variable.
+    let bind_variable = BIND_VARIABLE.get_or_init(|| {
+        Regex::new(r"(?P<cast>::[a-zA-Z_][a-zA-Z0-9_]*)|\$\d+|:[a-zA-Z_][a-zA-Z0-9_]*").unwrap()
+    });
+    bind_variable
+        .replace_all(query, |caps: &regex::Captures| {
+            if let Some(cast) = caps.name("cast") {
+                cast.as_str().to_string()
+            } else {
+                "?".to_string()
+            }
+        })
+        .into_owned()
+}
+
+fn replace_literals(query: &str) -> String {
+    static LITERAL: OnceLock<Regex> = OnceLock::new();
+    let literal = LITERAL.get_or_init(|| {
+        Regex::new(
+            r#"'(?:[^'\\]|\\.|'')*'|"(?:[^"\\]|\\.)*"|\b0x[0-9a-fA-F]+\b|\b\d+\.\d+\b|\b\d+\b"#,
+        )
+        .unwrap()
+    });
+    literal.replace_all(query, "?").into_owned()
+}
+
+fn collapse_in_lists(query: &str) -> String {
+    static IN_LIST: OnceLock<Regex> = OnceLock::new();
+    let in_list =
+        IN_LIST.get_or_init(|| Regex::new(r"\(\s*\?\s*(?:,\s*\?\s*)+\)").unwrap());
+    in_list.replace_all(query, "(?)").into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replaces_string_literals() {
+        assert_eq!(
+            obfuscate_query("SELECT * FROM users WHERE email = 'a@b.com'"),
+            "SELECT * FROM users WHERE email = ?"
+        );
+    }
+
+    #[test]
+    fn test_replaces_string_literal_with_doubled_quote_escape() {
+        assert_eq!(
+            obfuscate_query("SELECT * FROM users WHERE name = 'O''Brien'"),
+            "SELECT * FROM users WHERE name = ?"
+        );
+    }
+
+    #[test]
+    fn test_replaces_numeric_literals() {
+        assert_eq!(
+            obfuscate_query("SELECT * FROM users WHERE id = 42 AND score = 3.14"),
+            "SELECT * FROM users WHERE id = ? AND score = ?"
+        );
+    }
+
+    #[test]
+    fn test_replaces_bind_variables() {
+        assert_eq!(
+            obfuscate_query("SELECT * FROM users WHERE id = $1"),
+            "SELECT * FROM users WHERE id = ?"
+        );
+        assert_eq!(
+            obfuscate_query("SELECT * FROM users WHERE id = :user_id"),
+            "SELECT * FROM users WHERE id = ?"
+        );
+    }
+
+    #[test]
+    fn test_does_not_treat_type_cast_as_a_bind_variable() {
+        assert_eq!(
+            obfuscate_query("SELECT created_at::date, id::text FROM t WHERE x = 1"),
+            "SELECT created_at::date, id::text FROM t WHERE x = ?"
+        );
+    }
+
+    #[test]
+    fn test_collapses_in_lists() {
+        assert_eq!(
+            obfuscate_query("SELECT * FROM users WHERE id IN (1, 2, 3)"),
+            "SELECT * FROM users WHERE id IN (?)"
+        );
+    }
+
+    #[test]
+    fn test_strips_comments() {
+        assert_eq!(
+            obfuscate_query("SELECT * FROM users -- get everyone\nWHERE id = 1"),
+            "SELECT * FROM users \nWHERE id = ?"
+        );
+        assert_eq!(
+            obfuscate_query("SELECT /* all columns */ * FROM users WHERE id = 1"),
+            "SELECT  * FROM users WHERE id = ?"
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use crate::api::duration_to_nanos;
+use crate::model::{Span, Trace};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const BUCKET_DURATION: Duration = Duration::from_secs(10);
+const DEFAULT_ALPHA: f64 = 0.00775;
+
+/// Relative-accuracy latency sketch matching the Datadog agent's DDSketch: values are
+/// bucketed on a log scale of base gamma = (1+alpha)/(1-alpha) so that the value a
+/// quantile reports for a bucket is within relative error `alpha` of every value in it.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DDSketch {
+    alpha: f64,
+    gamma: f64,
+    buckets: HashMap<i32, u64>,
+}
+
+impl DDSketch {
+    pub fn new(alpha: f64) -> DDSketch {
+        DDSketch {
+            alpha,
+            gamma: (1.0 + alpha) / (1.0 - alpha),
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, value: u64) {
+        if value == 0 {
+            return;
+        }
+        let index = ((value as f64).ln() / self.gamma.ln()).ceil() as i32;
+        *self.buckets.entry(index).or_insert(0) += 1;
+    }
+
+    pub fn merge(&mut self, other: &DDSketch) {
+        for (index, count) in &other.buckets {
+            *self.buckets.entry(*index).or_insert(0) += count;
+        }
+    }
+
+    pub fn quantile(&self, q: f64) -> f64 {
+        let total: u64 = self.buckets.values().sum();
+        if total == 0 {
+            return 0.0;
+        }
+        let target = (q * total as f64).ceil().max(1.0) as u64;
+        let mut indices: Vec<&i32> = self.buckets.keys().collect();
+        indices.sort();
+        let mut seen = 0u64;
+        for index in indices {
+            seen += self.buckets[index];
+            if seen >= target {
+                return 2.0 * self.gamma.powi(*index) / (self.gamma + 1.0);
+            }
+        }
+        0.0
+    }
+}
+
+impl Default for DDSketch {
+    fn default() -> DDSketch {
+        DDSketch::new(DEFAULT_ALPHA)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct AggregationKey {
+    service: String,
+    name: String,
+    resource: String,
+    r#type: String,
+    http_status_code: String,
+    is_synthetics: bool,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ClientGroupedStat {
+    service: String,
+    name: String,
+    resource: String,
+    #[serde(rename = "type")]
+    span_type: String,
+    http_status_code: String,
+    synthetics: bool,
+    hits: u64,
+    errors: u64,
+    duration: u64,
+    top_level_hits: u64,
+    ok_summary: DDSketch,
+    error_summary: DDSketch,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ClientStatsBucket {
+    start: u64,
+    duration: u64,
+    stats: Vec<ClientGroupedStat>,
+}
+
+#[derive(Debug, Clone)]
+struct StatsBucket {
+    hits: u64,
+    errors: u64,
+    duration: u64,
+    top_level_hits: u64,
+    ok_summary: DDSketch,
+    error_summary: DDSketch,
+}
+
+impl StatsBucket {
+    fn new() -> StatsBucket {
+        StatsBucket {
+            hits: 0,
+            errors: 0,
+            duration: 0,
+            top_level_hits: 0,
+            ok_summary: DDSketch::default(),
+            error_summary: DDSketch::default(),
+        }
+    }
+
+    fn add_span(&mut self, span: &Span, is_top_level: bool) {
+        let duration_nanos = duration_to_nanos(span.duration);
+        self.hits += 1;
+        self.duration += duration_nanos;
+        if is_top_level {
+            self.top_level_hits += 1;
+        }
+        if span.error.is_some() {
+            self.errors += 1;
+            self.error_summary.add(duration_nanos);
+        } else {
+            self.ok_summary.add(duration_nanos);
+        }
+    }
+}
+
+/// Buckets spans into fixed 10-second windows and aggregates hit/error/latency stats
+/// locally, so callers still get service stats when the agent drops spans to sampling.
+pub struct Concentrator {
+    buckets: HashMap<(u64, AggregationKey), StatsBucket>,
+}
+
+impl Concentrator {
+    pub fn new() -> Concentrator {
+        Concentrator {
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn add_trace(&mut self, trace: &Trace, service: &str) {
+        let spans_by_id: HashMap<u64, &Span> =
+            trace.spans.iter().map(|span| (span.id, span)).collect();
+
+        for span in &trace.spans {
+            let is_top_level = crate::api::is_top_level(span, &spans_by_id, service);
+
+            let window_start = duration_to_nanos(
+                span.start
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default(),
+            ) / duration_to_nanos(BUCKET_DURATION)
+                * duration_to_nanos(BUCKET_DURATION);
+
+            let key = AggregationKey {
+                service: crate::api::resolve_service(span, service),
+                name: span.name.clone(),
+                resource: span.resource.clone(),
+                r#type: span.r#type.clone(),
+                http_status_code: span
+                    .http
+                    .as_ref()
+                    .map(|http| http.status_code.clone())
+                    .unwrap_or_default(),
+                is_synthetics: span
+                    .tags
+                    .get("synthetics")
+                    .map(|value| value == "true")
+                    .unwrap_or(false),
+            };
+
+            self.buckets
+                .entry((window_start, key))
+                .or_insert_with(StatsBucket::new)
+                .add_span(span, is_top_level);
+        }
+    }
+
+    /// Drains all accumulated buckets, returning them grouped by window as serializable
+    /// `ClientStatsBucket`s for the agent's `/v0.6/stats` endpoint.
+    pub fn flush(&mut self) -> Vec<ClientStatsBucket> {
+        let mut by_window: HashMap<u64, Vec<ClientGroupedStat>> = HashMap::new();
+        for ((window_start, key), bucket) in self.buckets.drain() {
+            by_window
+                .entry(window_start)
+                .or_default()
+                .push(ClientGroupedStat {
+                    service: key.service,
+                    name: key.name,
+                    resource: key.resource,
+                    span_type: key.r#type,
+                    http_status_code: key.http_status_code,
+                    synthetics: key.is_synthetics,
+                    hits: bucket.hits,
+                    errors: bucket.errors,
+                    duration: bucket.duration,
+                    top_level_hits: bucket.top_level_hits,
+                    ok_summary: bucket.ok_summary,
+                    error_summary: bucket.error_summary,
+                });
+        }
+
+        by_window
+            .into_iter()
+            .map(|(start, stats)| ClientStatsBucket {
+                start,
+                duration: duration_to_nanos(BUCKET_DURATION),
+                stats,
+            })
+            .collect()
+    }
+}
+
+impl Default for Concentrator {
+    fn default() -> Concentrator {
+        Concentrator::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ErrorInfo;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_ddsketch_quantile_within_relative_accuracy() {
+        let mut sketch = DDSketch::default();
+        for value in 1..=1000u64 {
+            sketch.add(value * 1_000_000);
+        }
+
+        let p50 = sketch.quantile(0.5);
+        let actual = 500.0 * 1_000_000.0;
+        let relative_error = (p50 - actual).abs() / actual;
+        assert!(relative_error < 0.01, "relative error was {}", relative_error);
+    }
+
+    #[test]
+    fn test_ddsketch_merge_is_additive() {
+        let mut a = DDSketch::default();
+        a.add(100);
+        let mut b = DDSketch::default();
+        b.add(100);
+        b.add(200);
+
+        a.merge(&b);
+
+        let index_100 = ((100f64).ln() / a.gamma.ln()).ceil() as i32;
+        let index_200 = ((200f64).ln() / a.gamma.ln()).ceil() as i32;
+        assert_eq!(a.buckets.get(&index_100), Some(&2));
+        assert_eq!(a.buckets.get(&index_200), Some(&1));
+        assert_eq!(a.buckets.values().sum::<u64>(), 3);
+    }
+
+    fn test_span(id: u64, start: SystemTime, duration: Duration, error: Option<ErrorInfo>) -> Span {
+        Span {
+            id,
+            parent_id: None,
+            service: None,
+            name: String::from("handler"),
+            resource: String::from("/foo"),
+            r#type: String::from("web"),
+            start,
+            duration,
+            http: None,
+            error,
+            sql: None,
+            tags: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_add_trace_aggregates_spans_with_the_same_key_into_one_bucket() {
+        let mut concentrator = Concentrator::new();
+        let start = SystemTime::now();
+        let trace = Trace {
+            id: 1,
+            priority: 1,
+            spans: vec![
+                test_span(1, start, Duration::from_millis(10), None),
+                test_span(2, start, Duration::from_millis(20), None),
+            ],
+        };
+
+        concentrator.add_trace(&trace, "service_name");
+        let buckets = concentrator.flush();
+
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].stats.len(), 1);
+        let stat = &buckets[0].stats[0];
+        assert_eq!(stat.hits, 2);
+        assert_eq!(
+            stat.duration,
+            duration_to_nanos(Duration::from_millis(10)) + duration_to_nanos(Duration::from_millis(20))
+        );
+        assert_eq!(stat.top_level_hits, 2);
+    }
+
+    #[test]
+    fn test_add_trace_routes_error_spans_to_error_summary_only() {
+        let mut concentrator = Concentrator::new();
+        let error = ErrorInfo {
+            r#type: String::from("Error"),
+            msg: String::from("boom"),
+            stack: String::new(),
+        };
+        let trace = Trace {
+            id: 2,
+            priority: 1,
+            spans: vec![test_span(1, SystemTime::now(), Duration::from_millis(10), Some(error))],
+        };
+
+        concentrator.add_trace(&trace, "service_name");
+        let buckets = concentrator.flush();
+
+        let stat = &buckets[0].stats[0];
+        assert_eq!(stat.hits, 1);
+        assert_eq!(stat.errors, 1);
+        assert_eq!(stat.error_summary.buckets.values().sum::<u64>(), 1);
+        assert_eq!(stat.ok_summary.buckets.values().sum::<u64>(), 0);
+    }
+
+    #[test]
+    fn test_add_trace_splits_spans_into_separate_10s_windows() {
+        let mut concentrator = Concentrator::new();
+        let trace = Trace {
+            id: 3,
+            priority: 1,
+            spans: vec![
+                test_span(
+                    1,
+                    std::time::UNIX_EPOCH + Duration::from_secs(0),
+                    Duration::from_millis(1),
+                    None,
+                ),
+                test_span(
+                    2,
+                    std::time::UNIX_EPOCH + Duration::from_secs(15),
+                    Duration::from_millis(1),
+                    None,
+                ),
+            ],
+        };
+
+        concentrator.add_trace(&trace, "service_name");
+        let mut buckets = concentrator.flush();
+        buckets.sort_by_key(|bucket| bucket.start);
+
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].start, 0);
+        assert_eq!(buckets[1].start, duration_to_nanos(BUCKET_DURATION));
+        assert_eq!(buckets[0].stats[0].hits, 1);
+        assert_eq!(buckets[1].stats[0].hits, 1);
+    }
+}